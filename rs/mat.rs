@@ -0,0 +1,127 @@
+use rhai::{CustomType, EvalAltResult, Module, TypeBuilder, FnNamespace};
+use ndarray::Array2;
+
+// 注册矩阵模块的核心类型与函数，是 math 模块 1-D Vec<f64> 的 2-D 补充。
+// 脚本侧通过 Matrix 句柄持有 ndarray::Array2<f64>，避免在多次调用间
+// 反复拆包/重建嵌套数组。
+
+/// 脚本可持有的矩阵句柄，内部即一个 `ndarray::Array2<f64>`。
+#[derive(Debug, Clone, CustomType)]
+pub struct Matrix(pub Array2<f64>);
+
+impl Matrix {
+    fn rows(&mut self) -> i64 {
+        self.0.nrows() as i64
+    }
+
+    fn cols(&mut self) -> i64 {
+        self.0.ncols() as i64
+    }
+
+    // 越界/负数下标返回脚本可捕获的错误，而不是让 ndarray 的索引 panic 搞垮整个宿主进程。
+    fn get(&mut self, row: i64, col: i64) -> Result<f64, Box<EvalAltResult>> {
+        if row < 0 || col < 0 || row as usize >= self.0.nrows() || col as usize >= self.0.ncols() {
+            return Err(format!(
+                "Matrix.get: 下标 ({row}, {col}) 超出范围 (矩阵是 {}x{})",
+                self.0.nrows(), self.0.ncols()
+            ).into());
+        }
+        Ok(self.0[[row as usize, col as usize]])
+    }
+}
+
+// 将脚本传入的 list_of_lists (Vec<Vec<f64>>) 转成 Array2<f64>；行参差不齐时返回脚本错误
+// 而不是 panic。
+fn rows_to_array2(rows: Vec<Vec<f64>>) -> Result<Array2<f64>, Box<EvalAltResult>> {
+    let nrows = rows.len();
+    let ncols = rows.first().map(|r| r.len()).unwrap_or(0);
+    if rows.iter().any(|r| r.len() != ncols) {
+        return Err(format!("mat.from_rows: 各行长度必须一致 (首行长度 {ncols})").into());
+    }
+    let flat: Vec<f64> = rows.into_iter().flatten().collect();
+    Array2::from_shape_vec((nrows, ncols), flat)
+        .map_err(|e| format!("mat.from_rows: {e}").into())
+}
+
+// 注册矩阵模块的核心函数
+pub fn create_module() -> Module {
+    let mut module = Module::new();
+    module.set_custom_type::<Matrix>("Matrix");
+
+    // 脚本调用: mat.from_rows(list_of_lists)
+    module.set_native_fn("from_rows", |rows: Vec<Vec<f64>>| -> Result<Matrix, Box<EvalAltResult>> {
+        Ok(Matrix(rows_to_array2(rows)?))
+    });
+
+    // 脚本调用: mat.matmul(a, b) - a 的列数必须等于 b 的行数
+    module.set_native_fn("matmul", |a: Matrix, b: Matrix| -> Result<Matrix, Box<EvalAltResult>> {
+        if a.0.ncols() != b.0.nrows() {
+            return Err(format!(
+                "mat.matmul: 维度不匹配 (a 是 {}x{}, b 是 {}x{})",
+                a.0.nrows(), a.0.ncols(), b.0.nrows(), b.0.ncols()
+            ).into());
+        }
+        Ok(Matrix(a.0.dot(&b.0)))
+    });
+
+    // 脚本调用: mat.transpose(a)
+    module.set_native_fn("transpose", |a: Matrix| -> Matrix {
+        Matrix(a.0.t().to_owned())
+    });
+
+    // 脚本调用: mat.cov(a) - 按列计算协方差矩阵 (行是样本，列是变量)
+    module.set_native_fn("cov", |a: Matrix| -> Matrix {
+        Matrix(covariance(&a.0))
+    });
+
+    // 脚本调用: mat.corr(a) - 按列计算相关系数矩阵
+    module.set_native_fn("corr", |a: Matrix| -> Matrix {
+        let cov = covariance(&a.0);
+        let ncols = cov.ncols();
+        let std_dev: Vec<f64> = (0..ncols).map(|i| cov[[i, i]].sqrt()).collect();
+
+        let mut corr = Array2::<f64>::zeros((ncols, ncols));
+        for i in 0..ncols {
+            for j in 0..ncols {
+                let denom = std_dev[i] * std_dev[j];
+                corr[[i, j]] = if denom == 0.0 { 0.0 } else { cov[[i, j]] / denom };
+            }
+        }
+        Matrix(corr)
+    });
+
+    // 形状/索引访问
+    // 脚本调用: a.rows(), a.cols(), a.get(row, col)
+    module.set_native_fn("rows", |m: &mut Matrix| -> i64 { m.rows() });
+    module.set_native_fn("cols", |m: &mut Matrix| -> i64 { m.cols() });
+    module.set_native_fn("get", |m: &mut Matrix, row: i64, col: i64| -> Result<f64, Box<EvalAltResult>> { m.get(row, col) });
+
+    module
+}
+
+// 按列计算协方差矩阵: 行是样本，列是变量，自由度取 n - 1。
+fn covariance(a: &Array2<f64>) -> Array2<f64> {
+    let nrows = a.nrows() as f64;
+    let ncols = a.ncols();
+    let means: Vec<f64> = (0..ncols)
+        .map(|j| a.column(j).iter().sum::<f64>() / nrows)
+        .collect();
+
+    let mut cov = Array2::<f64>::zeros((ncols, ncols));
+    if nrows < 2.0 {
+        return cov;
+    }
+
+    for i in 0..ncols {
+        for j in 0..ncols {
+            let sum: f64 = a
+                .column(i)
+                .iter()
+                .zip(a.column(j).iter())
+                .map(|(&x, &y)| (x - means[i]) * (y - means[j]))
+                .sum();
+            cov[[i, j]] = sum / (nrows - 1.0);
+        }
+    }
+    cov
+}
@@ -1,9 +1,18 @@
-use rhai::{Module, FnNamespace};
+use rhai::{CustomType, EvalAltResult, Map, Module, TypeBuilder, FnNamespace};
 use statrs::statistics::Statistics; // 引入 Rust 社区的高性能统计库
+use std::rc::Rc;
+use wide::f64x4; // SIMD 向量化运算，4 路 f64 通道
+use crate::mat::Matrix;
+
+/// 引用计数的共享数组句柄，供脚本重复传给多个 math.* 调用而不触发拷贝。
+/// 脚本调用: math.series(list) 构造，随后可直接传给 sum/mean/variance/std_dev/slope。
+#[derive(Debug, Clone, CustomType)]
+pub struct Series(pub Rc<Vec<f64>>);
 
 // 注册数学模块的核心函数
 pub fn create_module() -> Module {
     let mut module = Module::new();
+    module.set_custom_type::<Series>("Series");
 
     // 1. 基础聚合 (利用 Rust Iterator 的原生速度)
     // 脚本调用: math.sum(list)
@@ -18,17 +27,10 @@ pub fn create_module() -> Module {
 
     // 2. 高级统计 (利用 statrs 库)
     // 脚本调用: math.variance(list)
-    module.set_native_fn("variance", |arr: Vec<f64>| -> f64 {
-        if arr.len() < 2 { return 0.0; }
-        // 直接调用 statrs 库的优化实现，速度极快
-        arr.variance() 
-    });
+    module.set_native_fn("variance", |arr: Vec<f64>| -> f64 { variance_of(&arr) });
 
     // 脚本调用: math.std_dev(list)
-    module.set_native_fn("std_dev", |arr: Vec<f64>| -> f64 {
-        if arr.len() < 2 { return 0.0; }
-        arr.std_dev()
-    });
+    module.set_native_fn("std_dev", |arr: Vec<f64>| -> f64 { std_dev_of(&arr) });
 
     // 3. 线性回归斜率 (Slope) - 量化策略核心指标
     // 脚本调用: math.slope(list)
@@ -49,17 +51,382 @@ pub fn create_module() -> Module {
         if denominator == 0.0 { 0.0 } else { numerator / denominator }
     });
 
-    // 4. 向量运算 (简化版 SIMD)
+    // 4. 完整最小二乘回归 (斜率 + 截距 + 拟合优度 + 标准误)
+    // 脚本调用: math.linreg(list) -> #{ slope, intercept, r_squared, residual_std_err }
+    module.set_native_fn("linreg", |arr: Vec<f64>| -> Map {
+        let xs: Vec<f64> = (0..arr.len()).map(|i| i as f64).collect();
+        linreg_xy(&xs, &arr)
+    });
+
+    // 脚本调用: math.linreg_xy(xs, ys) -> #{ slope, intercept, r_squared, residual_std_err }
+    module.set_native_fn("linreg_xy", |xs: Vec<f64>, ys: Vec<f64>| -> Map {
+        linreg_xy(&xs, &ys)
+    });
+
+    // 脚本调用: math.predict(model, x) - 用 linreg/linreg_xy 的结果外推趋势值
+    module.set_native_fn("predict", |model: Map, x: f64| -> f64 {
+        let slope = model.get("slope").and_then(|v| v.as_float().ok()).unwrap_or(0.0);
+        let intercept = model.get("intercept").and_then(|v| v.as_float().ok()).unwrap_or(0.0);
+        slope * x + intercept
+    });
+
+    // 5. 向量运算 (SIMD 加速，4 路 f64 通道 + 标量尾部)
     // 脚本调用: math.v_add(list1, list2)
     module.set_native_fn("v_add", |a: Vec<f64>, b: Vec<f64>| -> Vec<f64> {
-        // 如果长度不等，取最短的
-        a.iter().zip(b.iter()).map(|(x, y)| x + y).collect()
+        simd_zip_with(&a, &b, |x, y| x + y, |x, y| x + y)
     });
 
     // 脚本调用: math.v_sub(list1, list2)
     module.set_native_fn("v_sub", |a: Vec<f64>, b: Vec<f64>| -> Vec<f64> {
-        a.iter().zip(b.iter()).map(|(x, y)| x - y).collect()
+        simd_zip_with(&a, &b, |x, y| x - y, |x, y| x - y)
+    });
+
+    // 脚本调用: math.v_mul(list1, list2)
+    module.set_native_fn("v_mul", |a: Vec<f64>, b: Vec<f64>| -> Vec<f64> {
+        simd_zip_with(&a, &b, |x, y| x * y, |x, y| x * y)
+    });
+
+    // 脚本调用: math.v_div(list1, list2)
+    module.set_native_fn("v_div", |a: Vec<f64>, b: Vec<f64>| -> Vec<f64> {
+        simd_zip_with(&a, &b, |x, y| x / y, |x, y| x / y)
+    });
+
+    // 脚本调用: math.v_scale(list, scalar)
+    module.set_native_fn("v_scale", |a: Vec<f64>, scalar: f64| -> Vec<f64> {
+        let factor = f64x4::splat(scalar);
+        let mut out = Vec::with_capacity(a.len());
+        let chunks = a.chunks_exact(4);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            let lanes = f64x4::new([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            out.extend_from_slice(&(lanes * factor).to_array());
+        }
+        out.extend(remainder.iter().map(|x| x * scalar));
+        out
+    });
+
+    // 6. 滚动窗口指标 (O(n) 滑动累加，而非逐窗口重新求和)
+    // 脚本调用: math.roll_mean(list, window)
+    module.set_native_fn("roll_mean", |arr: Vec<f64>, window: i64| -> Vec<f64> {
+        // 除数要用 roll_sum 内部实际生效的窗口 (window.max(1))，否则 window <= 0 会除以非正数
+        let window = window.max(1);
+        roll_sum(&arr, window).into_iter().map(|s| s / window as f64).collect()
+    });
+
+    // 脚本调用: math.roll_sum(list, window)
+    module.set_native_fn("roll_sum", |arr: Vec<f64>, window: i64| -> Vec<f64> {
+        roll_sum(&arr, window)
+    });
+
+    // 脚本调用: math.roll_std(list, window)
+    module.set_native_fn("roll_std", |arr: Vec<f64>, window: i64| -> Vec<f64> {
+        let window = window.max(1) as usize;
+        let n = arr.len();
+        let mut out = vec![0.0; n];
+        if window < 2 || window > n {
+            return out;
+        }
+
+        // sum / sum_of_squares 滑动窗口差分，避免每个窗口重新遍历
+        let mut sum: f64 = arr[..window].iter().sum();
+        let mut sum_sq: f64 = arr[..window].iter().map(|&x| x * x).sum();
+        let w = window as f64;
+        out[window - 1] = ((sum_sq - sum * sum / w) / (w - 1.0)).max(0.0).sqrt();
+
+        for i in window..n {
+            let entering = arr[i];
+            let leaving = arr[i - window];
+            sum += entering - leaving;
+            sum_sq += entering * entering - leaving * leaving;
+            out[i] = ((sum_sq - sum * sum / w) / (w - 1.0)).max(0.0).sqrt();
+        }
+
+        out
+    });
+
+    // 脚本调用: math.roll_slope(list, window)
+    module.set_native_fn("roll_slope", |arr: Vec<f64>, window: i64| -> Vec<f64> {
+        roll_slope(&arr, window)
+    });
+
+    // 7. 向量代数基础 (点积/范数/归一化/余弦相似度) + 共轭梯度求解器
+    // 脚本调用: math.dot(a, b) - SIMD 点积，不等长时取较短者
+    module.set_native_fn("dot", |a: Vec<f64>, b: Vec<f64>| -> f64 { dot(&a, &b) });
+
+    // 脚本调用: math.norm(a) - L2 范数
+    module.set_native_fn("norm", |a: Vec<f64>| -> f64 { norm(&a) });
+
+    // 脚本调用: math.normalize(a) - 归一化为单位向量，零向量原样返回
+    module.set_native_fn("normalize", |a: Vec<f64>| -> Vec<f64> {
+        let n = norm(&a);
+        if n == 0.0 { a } else { a.iter().map(|x| x / n).collect() }
+    });
+
+    // 脚本调用: math.cosine_sim(a, b) - 任一向量范数为 0 时返回 0.0
+    module.set_native_fn("cosine_sim", |a: Vec<f64>, b: Vec<f64>| -> f64 {
+        let denom = norm(&a) * norm(&b);
+        if denom == 0.0 { 0.0 } else { dot(&a, &b) / denom }
+    });
+
+    // 脚本调用: math.solve_cg(A, b) - A 必须是与 b 等长的对称正定方阵 (如回归正规方程 X^T X + λI)
+    module.set_native_fn("solve_cg", |a: Matrix, b: Vec<f64>| -> Result<Vec<f64>, Box<EvalAltResult>> {
+        if a.0.nrows() != b.len() || a.0.ncols() != b.len() {
+            return Err(format!(
+                "math.solve_cg: A 必须是 {0}x{0} 的方阵以匹配 b 的长度，实际是 {1}x{2}",
+                b.len(), a.0.nrows(), a.0.ncols()
+            ).into());
+        }
+        Ok(solve_cg(&a, &b, 1e-10, b.len()))
+    });
+
+    // 8. 共享数组句柄 (Rc<Vec<f64>>)，消除高频调用下的重复堆分配
+    // 脚本调用: math.series(list) - 构造一次，之后传给下面的重载不再拷贝数组
+    module.set_native_fn("series", |arr: Vec<f64>| -> Series { Series(Rc::new(arr)) });
+
+    // 以下是 sum/mean/variance/std_dev/slope 的 Series 重载：签名不同，Rhai 按类型分派，
+    // 直接读取 Rc<Vec<f64>> 里的数据而不拷贝数组。variance/std_dev 与 Vec<f64> 版本共用
+    // variance_of/std_dev_of；sum/mean 内联了相同的公式；slope 取 linreg_xy 结果中的斜率。
+    module.set_native_fn("sum", |s: Series| -> f64 { s.0.iter().sum() });
+
+    module.set_native_fn("mean", |s: Series| -> f64 {
+        if s.0.is_empty() { 0.0 } else { s.0.iter().sum::<f64>() / s.0.len() as f64 }
+    });
+
+    module.set_native_fn("variance", |s: Series| -> f64 { variance_of(&s.0) });
+
+    module.set_native_fn("std_dev", |s: Series| -> f64 { std_dev_of(&s.0) });
+
+    module.set_native_fn("slope", |s: Series| -> f64 {
+        let xs: Vec<f64> = (0..s.0.len()).map(|i| i as f64).collect();
+        let model = linreg_xy(&xs, &s.0);
+        model.get("slope").and_then(|v| v.as_float().ok()).unwrap_or(0.0)
     });
 
     module
+}
+
+// 共轭梯度法: 从 x0 = 0 出发，r0 = b，p0 = r0，每轮用矩阵-向量积和内积
+// 更新 x/r/p，直到残差范数低于容差或达到迭代上限。A 必须对称正定。
+fn solve_cg(a: &Matrix, b: &[f64], tol: f64, max_iter: usize) -> Vec<f64> {
+    let n = b.len();
+    let mut x = vec![0.0; n];
+    let mut r = b.to_vec();
+    let mut p = r.clone();
+    let mut rs_old = dot(&r, &r);
+
+    if rs_old.sqrt() < tol {
+        return x;
+    }
+
+    for _ in 0..max_iter {
+        let ap = matvec(a, &p);
+        let alpha = rs_old / dot(&p, &ap);
+
+        for i in 0..n {
+            x[i] += alpha * p[i];
+            r[i] -= alpha * ap[i];
+        }
+
+        let rs_new = dot(&r, &r);
+        if rs_new.sqrt() < tol {
+            break;
+        }
+
+        let beta = rs_new / rs_old;
+        for i in 0..n {
+            p[i] = r[i] + beta * p[i];
+        }
+        rs_old = rs_new;
+    }
+
+    x
+}
+
+// 矩阵-向量积，CG 迭代唯一需要的矩阵侧内核。
+fn matvec(a: &Matrix, v: &[f64]) -> Vec<f64> {
+    (0..a.0.nrows())
+        .map(|i| a.0.row(i).iter().zip(v.iter()).map(|(&x, &y)| x * y).sum())
+        .collect()
+}
+
+// 向量内积，回归/CG/余弦相似度共用的基础核。
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    let len = a.len().min(b.len());
+    let a = &a[..len];
+    let b = &b[..len];
+
+    let a_chunks = a.chunks_exact(4);
+    let b_chunks = b.chunks_exact(4);
+    let a_remainder = a_chunks.remainder();
+    let b_remainder = b_chunks.remainder();
+
+    let lane_sum: f64 = a_chunks
+        .zip(b_chunks)
+        .map(|(ac, bc)| {
+            let av = f64x4::new([ac[0], ac[1], ac[2], ac[3]]);
+            let bv = f64x4::new([bc[0], bc[1], bc[2], bc[3]]);
+            (av * bv).reduce_add()
+        })
+        .sum();
+
+    let tail_sum: f64 = a_remainder
+        .iter()
+        .zip(b_remainder.iter())
+        .map(|(&x, &y)| x * y)
+        .sum();
+
+    lane_sum + tail_sum
+}
+
+// L2 范数，供 normalize/cosine_sim 复用。
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+// variance/std_dev 在 Vec<f64> 和 Series 两种签名的重载之间共用，避免逻辑漂移。
+fn variance_of(arr: &[f64]) -> f64 {
+    if arr.len() < 2 { return 0.0; }
+    // 直接调用 statrs 库的优化实现，速度极快
+    arr.iter().copied().variance()
+}
+
+fn std_dev_of(arr: &[f64]) -> f64 {
+    if arr.len() < 2 { return 0.0; }
+    arr.iter().copied().std_dev()
+}
+
+// 滑动窗口求和: 进入一个新元素就加上它、滑出一个旧元素就减去它，整体 O(n)。
+// 窗口填满之前的下标留 0.0（脚本里按惯例用 0.0 表示“数据不足”）。
+fn roll_sum(arr: &[f64], window: i64) -> Vec<f64> {
+    let window = window.max(1) as usize;
+    let n = arr.len();
+    let mut out = vec![0.0; n];
+    if window > n {
+        return out;
+    }
+
+    let mut sum: f64 = arr[..window].iter().sum();
+    out[window - 1] = sum;
+
+    for i in window..n {
+        sum += arr[i] - arr[i - window];
+        out[i] = sum;
+    }
+
+    out
+}
+
+// 滑动窗口斜率: x 固定取窗口内的相对位置 0..window-1，所以 sum_x/sum_xx 对每个窗口都
+// 不变，只需算一次；sum_y 和 sum_xy 随窗口右移用恒等式增量更新，整体 O(n)。
+fn roll_slope(arr: &[f64], window: i64) -> Vec<f64> {
+    let window = window.max(1) as usize;
+    let n = arr.len();
+    let mut out = vec![0.0; n];
+    if window < 2 || window > n {
+        return out;
+    }
+
+    let w = window as f64;
+    let sum_x = w * (w - 1.0) / 2.0;
+    let sum_xx = (w - 1.0) * w * (2.0 * w - 1.0) / 6.0;
+    let denominator = w * sum_xx - sum_x * sum_x;
+
+    let mut sum_y: f64 = arr[..window].iter().sum();
+    let mut sum_xy: f64 = arr[..window].iter().enumerate().map(|(k, &y)| k as f64 * y).sum();
+
+    let slope_at = |sum_xy: f64, sum_y: f64| -> f64 {
+        let numerator = w * sum_xy - sum_x * sum_y;
+        if denominator == 0.0 { 0.0 } else { numerator / denominator }
+    };
+    out[window - 1] = slope_at(sum_xy, sum_y);
+
+    for i in window..n {
+        let leaving = arr[i - window];
+        let entering = arr[i];
+        // 窗口右移一格，相对位置整体减 1：sum_xy' = sum_xy - sum_y + leaving + (w-1)*entering
+        sum_xy = sum_xy - sum_y + leaving + (w - 1.0) * entering;
+        sum_y = sum_y - leaving + entering;
+        out[i] = slope_at(sum_xy, sum_y);
+    }
+
+    out
+}
+
+// 对 (xs, ys) 做最小二乘回归，复用 sum_x/sum_y/sum_xy/sum_xx 这组已有的求和，
+// 再加一个 sum_yy 就能把斜率、截距、拟合优度、残差标准误一并算出来。
+// 注意 residual_std_err 是残差的标准误 sqrt(SSE/(n-2))，不是斜率估计量的标准误
+// (后者还需再除以 sqrt(sum_xx - sum_x²/n))。
+fn linreg_xy(xs: &[f64], ys: &[f64]) -> Map {
+    let n = xs.len().min(ys.len()) as f64;
+    let mut result = Map::new();
+
+    if n < 2.0 {
+        result.insert("slope".into(), 0.0_f64.into());
+        result.insert("intercept".into(), 0.0_f64.into());
+        result.insert("r_squared".into(), 0.0_f64.into());
+        result.insert("residual_std_err".into(), 0.0_f64.into());
+        return result;
+    }
+
+    let xs = &xs[..n as usize];
+    let ys = &ys[..n as usize];
+
+    let sum_x: f64 = xs.iter().sum();
+    let sum_y: f64 = ys.iter().sum();
+    let sum_xy: f64 = xs.iter().zip(ys.iter()).map(|(&x, &y)| x * y).sum();
+    let sum_xx: f64 = xs.iter().map(|&x| x * x).sum();
+    let sum_yy: f64 = ys.iter().map(|&y| y * y).sum();
+
+    let denominator = n * sum_xx - sum_x * sum_x;
+    let slope = if denominator == 0.0 { 0.0 } else { (n * sum_xy - sum_x * sum_y) / denominator };
+    let intercept = (sum_y - slope * sum_x) / n;
+
+    let r_num = (n * sum_xy - sum_x * sum_y).powi(2);
+    let r_den = (n * sum_xx - sum_x * sum_x) * (n * sum_yy - sum_y * sum_y);
+    let r_squared = if r_den == 0.0 { 0.0 } else { r_num / r_den };
+
+    // 残差平方和可由已有求和项推出: SSE = sum_yy - intercept*sum_y - slope*sum_xy
+    // 对近乎完美的拟合，这个差分在浮点下可能略为负数，clamp 到 0 避免 sqrt 出 NaN
+    let sse = (sum_yy - intercept * sum_y - slope * sum_xy).max(0.0);
+    let residual_std_err = if n > 2.0 { (sse / (n - 2.0)).sqrt() } else { 0.0 };
+
+    result.insert("slope".into(), slope.into());
+    result.insert("intercept".into(), intercept.into());
+    result.insert("r_squared".into(), r_squared.into());
+    result.insert("residual_std_err".into(), residual_std_err.into());
+    result
+}
+
+// 对两个等长（或不等长，取较短者）的 f64 数组按 4 路 SIMD 通道逐元素运算，
+// 剩余不足 4 个的尾部元素用标量循环补齐。
+fn simd_zip_with(
+    a: &[f64],
+    b: &[f64],
+    lane_op: impl Fn(f64x4, f64x4) -> f64x4,
+    scalar_op: impl Fn(f64, f64) -> f64,
+) -> Vec<f64> {
+    let len = a.len().min(b.len());
+    let a = &a[..len];
+    let b = &b[..len];
+
+    let mut out = Vec::with_capacity(len);
+    let a_chunks = a.chunks_exact(4);
+    let b_chunks = b.chunks_exact(4);
+    let a_remainder = a_chunks.remainder();
+    let b_remainder = b_chunks.remainder();
+
+    for (ac, bc) in a_chunks.zip(b_chunks) {
+        let av = f64x4::new([ac[0], ac[1], ac[2], ac[3]]);
+        let bv = f64x4::new([bc[0], bc[1], bc[2], bc[3]]);
+        out.extend_from_slice(&lane_op(av, bv).to_array());
+    }
+
+    out.extend(
+        a_remainder
+            .iter()
+            .zip(b_remainder.iter())
+            .map(|(&x, &y)| scalar_op(x, y)),
+    );
+
+    out
 }
\ No newline at end of file